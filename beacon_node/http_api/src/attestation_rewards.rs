@@ -5,8 +5,15 @@ use eth2::lighthouse::attestation_rewards::{IdealAttestationRewards, TotalAttest
 use safe_arith::SafeArith;
 use slog::Logger;
 use participation_cache::ParticipationCache;
-use state_processing::{per_epoch_processing::altair::{participation_cache, rewards_and_penalties::get_flag_weight}, common::altair::{BaseRewardPerIncrement, get_base_reward}};
-use types::{Epoch, EthSpec};
+use state_processing::{
+    common::altair::{BaseRewardPerIncrement, get_base_reward},
+    per_epoch_processing::altair::{participation_cache, rewards_and_penalties::get_flag_weight},
+    per_epoch_processing::base::{
+        validator_statuses::ValidatorStatuses,
+        rewards_and_penalties::get_attestation_deltas_all,
+    },
+};
+use types::{BeaconState, ChainSpec, Epoch, EthSpec};
 use types::consts::altair::WEIGHT_DENOMINATOR;
 use types::consts::altair::{TIMELY_SOURCE_FLAG_INDEX, TIMELY_TARGET_FLAG_INDEX, TIMELY_HEAD_FLAG_INDEX};
 use warp_utils::reject::{custom_not_found};
@@ -16,7 +23,7 @@ pub fn compute_attestation_rewards<T: BeaconChainTypes>(
     epoch: Epoch,
     validators: Vec<ValidatorId>,
     log: Logger
-) -> Result<AttestationRewardsTBD, warp::Rejection> {    
+) -> Result<AttestationRewardsTBD, warp::Rejection> {
 
     //--- Get state ---//
     let spec = &chain.spec;
@@ -32,65 +39,137 @@ pub fn compute_attestation_rewards<T: BeaconChainTypes>(
         .get_state(&state_root, Some(state_slot))
         .map_err(warp_utils::reject::beacon_chain_error)?
         .ok_or(warp_utils::reject::custom_not_found("State not found".to_owned()))?;
-    
+
+    let validator_indices = resolve_validator_indices(&state, &validators)?;
+
+    let (ideal_rewards, mut total_rewards) = match &state {
+        BeaconState::Base(_) => compute_base_attestation_rewards(&state, spec)?,
+        _ => compute_altair_attestation_rewards(&state, spec)?,
+    };
+
+    if let Some(validator_indices) = validator_indices {
+        total_rewards.retain(|reward| validator_indices.contains(&reward.validator_index));
+    }
+
+    let execution_optimistic = chain
+        .is_optimistic_or_invalid_head()
+        .map_err(warp_utils::reject::beacon_chain_error)?;
+
+    let finalized = epoch <= chain.canonical_head.cached_head().finalized_checkpoint().epoch;
+
+    Ok(AttestationRewardsTBD {
+        execution_optimistic,
+        finalized,
+        ideal_rewards,
+        total_rewards,
+    })
+}
+
+/// Resolve the `validators` query filter (pubkeys or indices) against `state`. Returns
+/// `None` when the filter is empty, meaning "every eligible validator".
+fn resolve_validator_indices<E: EthSpec>(
+    state: &BeaconState<E>,
+    validators: &[ValidatorId],
+) -> Result<Option<Vec<u64>>, warp::Rejection> {
+
+    if validators.is_empty() {
+        return Ok(None);
+    }
+
+    validators
+        .iter()
+        .map(|validator_id| match validator_id {
+            ValidatorId::Index(index) => {
+                if (*index as usize) < state.validators().len() {
+                    Ok(*index)
+                } else {
+                    Err(custom_not_found(format!("Unknown validator index {}", index)))
+                }
+            }
+            ValidatorId::PublicKey(pubkey) => state
+                .get_validator_index(pubkey)
+                .map_err(|_| custom_not_found(format!("Unable to resolve validator index for {:?}", pubkey)))?
+                .map(|index| index as u64)
+                .ok_or_else(|| custom_not_found(format!("Unknown validator {:?}", pubkey))),
+        })
+        .collect::<Result<Vec<u64>, warp::Rejection>>()
+        .map(Some)
+}
+
+/// Altair and later: rewards are derived from the participation flags recorded by the
+/// state, so the `inclusion_delay` component folded into the flag rewards and is left as 0.
+fn compute_altair_attestation_rewards<E: EthSpec>(
+    state: &BeaconState<E>,
+    spec: &ChainSpec,
+) -> Result<(Vec<IdealAttestationRewards>, Vec<TotalAttestationRewards>), warp::Rejection> {
+
     //--- Calculate ideal_rewards ---//
-    let participation_cache = ParticipationCache::new(&state, spec)
+    let participation_cache = ParticipationCache::new(state, spec)
         .map_err(|e| custom_not_found(format!("Unable to get participation_cache! {:?}", e)))?;
-    
+
     let previous_epoch = state.previous_epoch();
 
-    let mut ideal_rewards_hashmap = HashMap::new();
+    let is_in_inactivity_leak = state.is_in_inactivity_leak(previous_epoch, &spec);
 
-    let flag_index = 0;
-    let weight = 0;
-    let base_reward = 0;
-    let effective_balance_eth = 0;
+    // Per-flag ideal reward, keyed on (flag_index, effective_balance_eth).
+    let mut ideal_rewards_hashmap = HashMap::new();
+    // Per-flag weight and unslashed participating indices, needed again below when
+    // working out each validator's actual reward for that flag.
+    let mut flag_weights = HashMap::new();
+    let mut flag_participants = HashMap::new();
 
-    for flag_index in [TIMELY_SOURCE_FLAG_INDEX, TIMELY_TARGET_FLAG_INDEX, TIMELY_HEAD_FLAG_INDEX].iter() {
+    for flag_index in [TIMELY_SOURCE_FLAG_INDEX, TIMELY_TARGET_FLAG_INDEX, TIMELY_HEAD_FLAG_INDEX] {
 
-        let weight = get_flag_weight(*flag_index)
+        let weight = get_flag_weight(flag_index)
             .map_err(|e| custom_not_found(format!("Unable to get weight! {:?}", e)))?;
 
-        let unslashed_participating_indices = participation_cache.get_unslashed_participating_indices(*flag_index, previous_epoch)
-            .map_err(|e| custom_not_found(format!("Unable to get unslashed_participating_indices! {:?}", e)))?;   
+        let unslashed_participating_indices = participation_cache.get_unslashed_participating_indices(flag_index, previous_epoch)
+            .map_err(|e| custom_not_found(format!("Unable to get unslashed_participating_indices! {:?}", e)))?;
 
         let unslashed_participating_balance = unslashed_participating_indices.total_balance()
-            .map_err(|e| custom_not_found(format!("Unable to get unslashed_participating_balance! {:?}", e)))?;    
-        
+            .map_err(|e| custom_not_found(format!("Unable to get unslashed_participating_balance! {:?}", e)))?;
+
         let unslashed_participating_increments = unslashed_participating_balance.safe_div(spec.effective_balance_increment)
             .map_err(|e| custom_not_found(format!("Unable to get unslashed_participating_increments! {:?}", e)))?;
 
         let total_active_balance = participation_cache.current_epoch_total_active_balance();
-        
+
         let active_increments = total_active_balance.safe_div(spec.effective_balance_increment)
-            .map_err(|e| custom_not_found(format!("Unable to get active_increments! {:?}", e)))?;            
-        
+            .map_err(|e| custom_not_found(format!("Unable to get active_increments! {:?}", e)))?;
+
         let base_reward_per_increment = BaseRewardPerIncrement::new(total_active_balance, spec)
             .map_err(|e| custom_not_found(format!("Unable to get base_reward_per_increment! {:?}", e)))?;
-        
+
         for effective_balance_eth in 0..=32 {
-            
-            let base_reward = get_base_reward(&state, effective_balance_eth, base_reward_per_increment, spec);
 
-            let base_reward = base_reward
-                .map_err(|e| warp_utils::reject::custom_not_found(format!("Unable to get base_reward! {:?}", e)))?;
+            // `get_base_reward` takes a validator *index* (it reads that validator's own
+            // effective balance from `state`), so it cannot be used to ask "what would the
+            // base reward be for a validator with this bucketed balance". One increment is
+            // one whole ETH here, so the base reward for the bucket is just
+            // `effective_balance_eth * base_reward_per_increment`.
+            let base_reward = effective_balance_eth
+                .safe_mul(base_reward_per_increment.as_u64())
+                .map_err(|_| warp_utils::reject::custom_server_error("Unable to calculate base_reward".to_owned()))?;
 
             let reward_numerator = base_reward
                 .safe_mul(weight)
                 .and_then(|reward_numerator| reward_numerator.safe_mul(unslashed_participating_increments))
                 .map_err(|_| warp_utils::reject::custom_server_error("Unable to calculate reward numerator".to_owned()))?;
-        
+
             let reward = reward_numerator
                 .safe_div(active_increments)
                 .and_then(|reward| reward.safe_div(WEIGHT_DENOMINATOR))
                 .map_err(|_| warp_utils::reject::custom_server_error("Unable to calculate reward".to_owned()))?;
-            
-            if !state.is_in_inactivity_leak(previous_epoch, &spec) {
+
+            if !is_in_inactivity_leak {
                 ideal_rewards_hashmap.insert((flag_index, effective_balance_eth), reward);
             } else {
                 ideal_rewards_hashmap.insert((flag_index, effective_balance_eth), 0);
-            }  
+            }
         }
+
+        flag_weights.insert(flag_index, weight);
+        flag_participants.insert(flag_index, unslashed_participating_indices);
     }
 
     //--- Calculate actual rewards ---//
@@ -98,49 +177,167 @@ pub fn compute_attestation_rewards<T: BeaconChainTypes>(
 
     let index = participation_cache.eligible_validator_indices();
 
+    // `base_reward_per_increment` only depends on the total active balance, which is
+    // fixed for the epoch, so it is computed once rather than per validator per flag.
+    let base_reward_per_increment = BaseRewardPerIncrement::new(participation_cache.current_epoch_total_active_balance(), spec)
+        .map_err(|e| custom_not_found(format!("Unable to get base_reward_per_increment! {:?}", e)))?;
+
     for validator_index in index {
 
         let eligible = state.is_eligible_validator(previous_epoch, *validator_index)
         .map_err(|_| warp_utils::reject::custom_server_error("Unable to get eligible".to_owned()))?;
 
-        let total_reward = if !eligible {
-            0u64
+        let (source_reward, target_reward, head_reward) = if !eligible {
+            (0i64, 0i64, 0i64)
         } else {
-            let voted_correctly = participation_cache.get_unslashed_participating_indices(flag_index, previous_epoch).is_ok();
-            if voted_correctly {
-                *ideal_rewards_hashmap.get(&(&flag_index, effective_balance_eth)).unwrap_or(&0)
-            } else {
-                (-(base_reward as i64 as i128) * weight as i128 / WEIGHT_DENOMINATOR as i128) as u64
-            }
+            let effective_balance = state
+                .get_effective_balance(*validator_index)
+                .map_err(|_| warp_utils::reject::custom_server_error("Unable to get effective_balance".to_owned()))?;
+
+            let effective_balance_eth = effective_balance
+                .safe_div(spec.effective_balance_increment)
+                .map_err(|_| warp_utils::reject::custom_server_error("Unable to get effective_balance_eth".to_owned()))?
+                .min(32);
+
+            // Shared by all three flags below instead of being re-derived per flag.
+            let base_reward = get_base_reward(state, *validator_index, base_reward_per_increment, spec)
+                .map_err(|e| warp_utils::reject::custom_not_found(format!("Unable to get base_reward! {:?}", e)))?;
+
+            let flag_reward = |flag_index: usize, penalize_absent: bool| -> Result<i64, warp::Rejection> {
+                let weight = *flag_weights.get(&flag_index).unwrap_or(&0);
+
+                let voted_correctly = flag_participants
+                    .get(&flag_index)
+                    .map(|participants| participants.contains(*validator_index))
+                    .transpose()
+                    .map_err(|_| warp_utils::reject::custom_server_error("Unable to check participation".to_owned()))?
+                    .unwrap_or(false);
+
+                if voted_correctly {
+                    Ok(*ideal_rewards_hashmap.get(&(flag_index, effective_balance_eth)).unwrap_or(&0) as i64)
+                } else if penalize_absent {
+                    // Unlike the reward, the source/target penalty applies even during an
+                    // inactivity leak — only the positive reward is withheld then.
+                    Ok(-((base_reward as i128 * weight as i128 / WEIGHT_DENOMINATOR as i128) as i64))
+                } else {
+                    Ok(0)
+                }
+            };
+
+            let source_reward = flag_reward(TIMELY_SOURCE_FLAG_INDEX, true)?;
+            let target_reward = flag_reward(TIMELY_TARGET_FLAG_INDEX, true)?;
+            // A missed head vote is never penalized, only the ideal reward is withheld.
+            let head_reward = flag_reward(TIMELY_HEAD_FLAG_INDEX, false)?;
+
+            (source_reward, target_reward, head_reward)
         };
-        rewards.push((*validator_index, total_reward));
+
+        rewards.push((*validator_index, source_reward, target_reward, head_reward));
     }
 
-    //TODO Check target and source
-    let ideal_rewards: Vec<IdealAttestationRewards> = ideal_rewards_hashmap.iter().map(|((flag_index, effective_balance_eth), reward)| {
+    let ideal_rewards: Vec<IdealAttestationRewards> = (0..=32u64).map(|effective_balance_eth| {
         IdealAttestationRewards {
-            effective_balance: *effective_balance_eth as u64,
-            head: *reward,
-            target: 0,
-            source: 0,
+            effective_balance: effective_balance_eth,
+            head: *ideal_rewards_hashmap.get(&(TIMELY_HEAD_FLAG_INDEX, effective_balance_eth)).unwrap_or(&0),
+            target: *ideal_rewards_hashmap.get(&(TIMELY_TARGET_FLAG_INDEX, effective_balance_eth)).unwrap_or(&0),
+            source: *ideal_rewards_hashmap.get(&(TIMELY_SOURCE_FLAG_INDEX, effective_balance_eth)).unwrap_or(&0),
         }
     }).collect();
 
-    //TODO Check target, source, and inclusion_delay
-    let total_rewards: Vec<TotalAttestationRewards> = rewards.into_iter().map(|(validator_index, reward)| {
+    let total_rewards: Vec<TotalAttestationRewards> = rewards.into_iter().map(|(validator_index, source, target, head)| {
         TotalAttestationRewards {
             validator_index: validator_index as u64,
-            head: reward as i64,
-            target: 0,
-            source: 0,
+            head,
+            target,
+            source,
             inclusion_delay: 0,
         }
     }).collect();
 
-    Ok(AttestationRewardsTBD{
-        execution_optimistic: false,
-        finalized: false,
-        ideal_rewards,
-        total_rewards,
-    })
-}
\ No newline at end of file
+    Ok((ideal_rewards, total_rewards))
+}
+
+/// Base/phase0: there are no participation flags, so rewards come from replaying the
+/// phase0 `get_attestation_deltas` accounting, which also yields a real `inclusion_delay`
+/// reward per attester (the component that, post-Altair, is folded into the flag rewards).
+fn compute_base_attestation_rewards<E: EthSpec>(
+    state: &BeaconState<E>,
+    spec: &ChainSpec,
+) -> Result<(Vec<IdealAttestationRewards>, Vec<TotalAttestationRewards>), warp::Rejection> {
+
+    let previous_epoch = state.previous_epoch();
+
+    let mut validator_statuses = ValidatorStatuses::new(state, spec)
+        .map_err(|e| custom_not_found(format!("Unable to get validator_statuses! {:?}", e)))?;
+
+    validator_statuses
+        .process_attestations(state)
+        .map_err(|e| custom_not_found(format!("Unable to process_attestations! {:?}", e)))?;
+
+    let deltas = get_attestation_deltas_all(state, &validator_statuses, spec)
+        .map_err(|e| custom_not_found(format!("Unable to get attestation_deltas! {:?}", e)))?;
+
+    // Match the Altair branch: only report eligible validators, not every index in the
+    // registry.
+    let total_rewards: Vec<TotalAttestationRewards> = deltas
+        .into_iter()
+        .enumerate()
+        .filter_map(|(validator_index, delta)| {
+            match state.is_eligible_validator(previous_epoch, validator_index) {
+                Ok(true) => Some(Ok(TotalAttestationRewards {
+                    validator_index: validator_index as u64,
+                    source: delta.source_delta.rewards as i64 - delta.source_delta.penalties as i64,
+                    target: delta.target_delta.rewards as i64 - delta.target_delta.penalties as i64,
+                    head: delta.head_delta.rewards as i64 - delta.head_delta.penalties as i64,
+                    inclusion_delay: delta.inclusion_delay_delta.rewards as i64 - delta.inclusion_delay_delta.penalties as i64,
+                })),
+                Ok(false) => None,
+                Err(_) => Some(Err(warp_utils::reject::custom_server_error("Unable to get eligible".to_owned()))),
+            }
+        })
+        .collect::<Result<Vec<_>, warp::Rejection>>()?;
+
+    // There is no per-validator "ideal" table in phase0 the way Altair's flag weights
+    // give one, but the phase0 base reward itself (`effective_balance * BASE_REWARD_FACTOR
+    // / isqrt(total_active_balance) / BASE_REWARDS_PER_EPOCH`) only depends on the
+    // effective balance bucket and the total active balance, so the same per-bucket table
+    // shape still applies; each of source/target/head is worth one whole base reward.
+    let total_active_balance = state
+        .get_total_active_balance()
+        .map_err(|_| warp_utils::reject::custom_server_error("Unable to get total_active_balance".to_owned()))?;
+
+    let total_balance_sqrt = integer_sqrt(total_active_balance).max(1);
+
+    let ideal_rewards: Vec<IdealAttestationRewards> = (0..=32u64).map(|effective_balance_eth| {
+        let effective_balance = effective_balance_eth.saturating_mul(spec.effective_balance_increment);
+
+        let base_reward = effective_balance
+            .safe_mul(spec.base_reward_factor)
+            .and_then(|v| v.safe_div(total_balance_sqrt))
+            .and_then(|v| v.safe_div(spec.base_rewards_per_epoch))
+            .unwrap_or(0);
+
+        IdealAttestationRewards {
+            effective_balance: effective_balance_eth,
+            head: base_reward,
+            target: base_reward,
+            source: base_reward,
+        }
+    }).collect();
+
+    Ok((ideal_rewards, total_rewards))
+}
+
+/// `isqrt` per the spec's `integer_squareroot`: the largest `x` such that `x * x <= n`.
+fn integer_sqrt(n: u64) -> u64 {
+    if n == 0 {
+        return 0;
+    }
+    let mut x = n;
+    let mut y = x.safe_add(1).unwrap_or(x).safe_div(2).unwrap_or(x);
+    while y < x {
+        x = y;
+        y = x.safe_add(n.safe_div(x).unwrap_or(0)).unwrap_or(x).safe_div(2).unwrap_or(x);
+    }
+    x
+}
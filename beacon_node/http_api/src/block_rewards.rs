@@ -0,0 +1,283 @@
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use beacon_chain::{BeaconChain, BeaconChainTypes};
+use eth2::lighthouse::{BlockRewardsTBD, StandardBlockReward};
+use safe_arith::SafeArith;
+use slog::{debug, Logger};
+use state_processing::{
+    common::{get_attestation_participation_flag_indices, altair::{BaseRewardPerIncrement, get_base_reward}},
+    per_block_processing::altair::sync_committee::compute_sync_aggregate_rewards,
+};
+use types::EthSpec;
+use types::consts::altair::{PROPOSER_WEIGHT, WEIGHT_DENOMINATOR};
+use types::consts::altair::{TIMELY_SOURCE_FLAG_INDEX, TIMELY_TARGET_FLAG_INDEX, TIMELY_HEAD_FLAG_INDEX};
+use warp_utils::reject::{beacon_chain_error, custom_bad_request, custom_not_found};
+use crate::BlockId;
+
+pub fn compute_block_rewards<T: BeaconChainTypes>(
+    chain: Arc<BeaconChain<T>>,
+    block_id: BlockId,
+    log: Logger
+) -> Result<BlockRewardsTBD, warp::Rejection> {
+
+    let spec = &chain.spec;
+
+    let (block, execution_optimistic) = block_id.blinded_block(&chain)?;
+
+    let block_ref = block.message();
+
+    let parent_root = block.parent_root();
+
+    let parent_block = chain
+        .get_blinded_block(&parent_root)
+        .map_err(beacon_chain_error)?
+        .ok_or_else(|| custom_not_found(format!("Parent block not found: {:?}", parent_root)))?;
+
+    let state_root = parent_block.state_root();
+
+    let mut state = chain
+        .get_state(&state_root, Some(parent_block.slot()))
+        .map_err(beacon_chain_error)?
+        .ok_or_else(|| custom_not_found(String::from("Unable to get pre-state")))?;
+
+    debug!(
+        log,
+        "Retrieving block rewards";
+        "block_root" => ?block.canonical_root(),
+        "slot" => block.slot(),
+    );
+
+    let total_active_balance = state
+        .get_total_active_balance()
+        .map_err(|_| custom_bad_request(String::from("Unable to get total_active_balance")))?;
+
+    let base_reward_per_increment = BaseRewardPerIncrement::new(total_active_balance, spec)
+        .map_err(|_| custom_bad_request(String::from("Unable to get base_reward_per_increment")))?;
+
+    let current_epoch = state.current_epoch();
+
+    // Constant across every attestation in the block: as in `process_attestation`,
+    // `base_reward * weight` for every newly-set flag of one attestation is accumulated
+    // into a single numerator, which is divided once per attestation rather than per flag.
+    let proposer_reward_denominator = WEIGHT_DENOMINATOR
+        .safe_sub(PROPOSER_WEIGHT)
+        .and_then(|w| w.safe_mul(WEIGHT_DENOMINATOR))
+        .and_then(|w| w.safe_div(PROPOSER_WEIGHT))
+        .map_err(|_| custom_bad_request(String::from("Unable to calculate proposer_reward_denominator")))?;
+
+    //--- Attestations ---//
+    // Mirrors `process_attestation`: the proposer is only credited for a flag the first
+    // time it is set for a validator, so we replay the pre-state participation and mark
+    // flags as we go, exactly as block processing would.
+    let mut attestation_total_reward = 0u64;
+
+    for attestation in block_ref.body().attestations() {
+
+        let data = &attestation.data;
+
+        let mut attestation_reward_numerator = 0u64;
+
+        let participation_flag_indices = get_attestation_participation_flag_indices(
+            &state,
+            data,
+            block.slot().safe_sub(data.slot).map_err(|_| custom_bad_request(String::from("Unable to get inclusion_delay")))?,
+            spec,
+        )
+        .map_err(|_| custom_bad_request(String::from("Unable to get participation_flag_indices")))?;
+
+        let committee = state
+            .get_beacon_committee(data.slot, data.index)
+            .map_err(|_| custom_bad_request(String::from("Unable to get beacon_committee")))?;
+
+        let attesting_indices = attestation
+            .get_attesting_indices(committee.committee)
+            .map_err(|_| custom_bad_request(String::from("Unable to get attesting_indices")))?;
+
+        let is_current_epoch = data.target.epoch == current_epoch;
+
+        for validator_index in attesting_indices {
+
+            let base_reward = get_base_reward(&state, validator_index as usize, base_reward_per_increment, spec)
+                .map_err(|_| custom_bad_request(String::from("Unable to get base_reward")))?;
+
+            for flag_index in [TIMELY_SOURCE_FLAG_INDEX, TIMELY_TARGET_FLAG_INDEX, TIMELY_HEAD_FLAG_INDEX] {
+
+                if !participation_flag_indices.contains(&flag_index) {
+                    continue;
+                }
+
+                let epoch_participation = if is_current_epoch {
+                    state.current_epoch_participation_mut()
+                } else {
+                    state.previous_epoch_participation_mut()
+                }
+                .map_err(|_| custom_bad_request(String::from("Unable to get epoch_participation")))?;
+
+                let validator_participation = epoch_participation
+                    .get_mut(validator_index as usize)
+                    .ok_or_else(|| custom_bad_request(format!("Validator index out of bounds: {}", validator_index)))?;
+
+                let already_has_flag = validator_participation
+                    .has_flag(flag_index)
+                    .map_err(|_| custom_bad_request(String::from("Unable to check participation flag")))?;
+
+                // The proposer is only ever paid the first time a flag is set for a
+                // validator; a later attestation (or aggregate) re-affirming the same
+                // vote earns nothing.
+                if already_has_flag {
+                    continue;
+                }
+
+                validator_participation
+                    .add_flag(flag_index)
+                    .map_err(|_| custom_bad_request(String::from("Unable to set participation flag")))?;
+
+                let weight = state_processing::per_epoch_processing::altair::rewards_and_penalties::get_flag_weight(flag_index)
+                    .map_err(|_| custom_bad_request(String::from("Unable to get weight")))?;
+
+                let reward_numerator = base_reward
+                    .safe_mul(weight)
+                    .map_err(|_| custom_bad_request(String::from("Unable to calculate reward_numerator")))?;
+
+                attestation_reward_numerator = attestation_reward_numerator.safe_add(reward_numerator)
+                    .map_err(|_| custom_bad_request(String::from("Unable to accumulate attestation_reward_numerator")))?;
+            }
+        }
+
+        attestation_total_reward = attestation_reward_numerator
+            .safe_div(proposer_reward_denominator)
+            .and_then(|reward| attestation_total_reward.safe_add(reward))
+            .map_err(|_| custom_bad_request(String::from("Unable to calculate attestation_total_reward")))?;
+    }
+
+    //--- Sync aggregate ---//
+    let sync_aggregate_reward = if let Ok(sync_aggregate) = block_ref.body().sync_aggregate() {
+        let (_, proposer_reward_per_bit) = compute_sync_aggregate_rewards(&state, spec)
+            .map_err(|_| custom_bad_request(String::from("Unable to get sync_aggregate_rewards")))?;
+
+        sync_aggregate
+            .sync_committee_bits
+            .iter()
+            .filter(|bit| *bit)
+            .count() as u64 * proposer_reward_per_bit
+    } else {
+        0
+    };
+
+    //--- Slashings ---//
+    // Each slashed validator is credited with the proposer's
+    // `PROPOSER_WEIGHT / WEIGHT_DENOMINATOR` share of its whistleblower reward; the
+    // whistleblower's remaining share is not counted in these components.
+    let mut already_slashed = HashSet::new();
+
+    let proposer_slashing_reward = block_ref
+        .body()
+        .proposer_slashings()
+        .iter()
+        .map(|proposer_slashing| {
+            let slashed_index = proposer_slashing.signed_header_1.message.proposer_index;
+
+            if !already_slashed.insert(slashed_index) {
+                return Ok(0);
+            }
+
+            let offending_validator = state
+                .get_validator(slashed_index as usize)
+                .map_err(|_| custom_bad_request(String::from("Unable to get slashed validator")))?;
+
+            if offending_validator.slashed {
+                return Ok(0);
+            }
+
+            whistleblower_proposer_reward(offending_validator.effective_balance, spec)
+        })
+        .collect::<Result<Vec<u64>, warp::Rejection>>()?
+        .iter()
+        .try_fold(0u64, |acc, reward| acc.safe_add(*reward))
+        .map_err(|_| custom_bad_request(String::from("Unable to accumulate proposer_slashing_reward")))?;
+
+    let attester_slashing_reward = block_ref
+        .body()
+        .attester_slashings()
+        .iter()
+        .flat_map(|attester_slashing| {
+            let attestation_1_indices: BTreeSet<u64> = attester_slashing
+                .attestation_1
+                .attesting_indices
+                .iter()
+                .copied()
+                .collect();
+            let attestation_2_indices: BTreeSet<u64> = attester_slashing
+                .attestation_2
+                .attesting_indices
+                .iter()
+                .copied()
+                .collect();
+
+            // Only validators attesting to *both* sides of the slashing are slashable.
+            attestation_1_indices
+                .intersection(&attestation_2_indices)
+                .copied()
+                .collect::<Vec<u64>>()
+        })
+        .map(|slashed_index| {
+            if !already_slashed.insert(slashed_index) {
+                return Ok(0);
+            }
+
+            let offending_validator = state
+                .get_validator(slashed_index as usize)
+                .map_err(|_| custom_bad_request(String::from("Unable to get slashed validator")))?;
+
+            if offending_validator.slashed {
+                return Ok(0);
+            }
+
+            whistleblower_proposer_reward(offending_validator.effective_balance, spec)
+        })
+        .collect::<Result<Vec<u64>, warp::Rejection>>()?
+        .iter()
+        .try_fold(0u64, |acc, reward| acc.safe_add(*reward))
+        .map_err(|_| custom_bad_request(String::from("Unable to accumulate attester_slashing_reward")))?;
+
+    let total = attestation_total_reward
+        .safe_add(sync_aggregate_reward)
+        .and_then(|sum| sum.safe_add(proposer_slashing_reward))
+        .and_then(|sum| sum.safe_add(attester_slashing_reward))
+        .map_err(|_| custom_bad_request(String::from("Unable to calculate total")))?;
+
+    debug!(
+        log,
+        "Retrieved block reward";
+        "total" => total,
+    );
+
+    let finalized = block.slot().epoch(T::EthSpec::slots_per_epoch())
+        <= chain.canonical_head.cached_head().finalized_checkpoint().epoch;
+
+    Ok(BlockRewardsTBD {
+        execution_optimistic,
+        finalized,
+        data: StandardBlockReward {
+            proposer_index: block_ref.proposer_index(),
+            total,
+            attestations: attestation_total_reward,
+            sync_aggregate: sync_aggregate_reward,
+            proposer_slashings: proposer_slashing_reward,
+            attester_slashings: attester_slashing_reward,
+        },
+    })
+}
+
+/// The proposer's share of a whistleblower reward: `PROPOSER_WEIGHT / WEIGHT_DENOMINATOR`
+/// of `effective_balance / WHISTLEBLOWER_REWARD_QUOTIENT`.
+fn whistleblower_proposer_reward(effective_balance: u64, spec: &types::ChainSpec) -> Result<u64, warp::Rejection> {
+    let whistleblower_reward = effective_balance
+        .safe_div(spec.whistleblower_reward_quotient)
+        .map_err(|_| custom_bad_request(String::from("Unable to calculate whistleblower_reward")))?;
+
+    whistleblower_reward
+        .safe_mul(PROPOSER_WEIGHT)
+        .and_then(|reward| reward.safe_div(WEIGHT_DENOMINATOR))
+        .map_err(|_| custom_bad_request(String::from("Unable to calculate proposer's share of whistleblower_reward")))
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// Response for `GET /eth/v1/beacon/rewards/blocks/{block_id}`, mirroring
+/// `AttestationRewardsTBD`/`SyncCommitteeAttestationRewards`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockRewardsTBD {
+    pub execution_optimistic: bool,
+    pub finalized: bool,
+    pub data: StandardBlockReward,
+}
+
+/// Breakdown of the rewards a block's proposer earned, in Gwei.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StandardBlockReward {
+    pub proposer_index: u64,
+    pub total: u64,
+    pub attestations: u64,
+    pub sync_aggregate: u64,
+    pub proposer_slashings: u64,
+    pub attester_slashings: u64,
+}